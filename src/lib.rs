@@ -1,116 +1,1293 @@
-pub struct HashMap {
-    buckets: Vec<Vec<(String, i32)>>,
+use std::borrow::Borrow;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use memmap2::MmapMut;
+
+pub struct HashMap<K, V, S = DefaultHasher> {
+    // One shard per logical AnchorHash bucket. In the default (single-table)
+    // mode there is exactly one shard and `anchor` is `None`; with a
+    // consistent-hashing placement strategy `shards` is sized to the anchor
+    // capacity and keys are routed through `anchor`.
+    shards: Vec<Table<K, V>>,
+    anchor: Option<Anchor>,
     entries_count: u32,
+    // Sum of every key's reference count; `entries_count` counts distinct keys.
+    total_references: u64,
+    hash_builder: S,
+    // When set, every mutation is mirrored into a memory-mapped append-only
+    // log on disk so the map can outlive the process (Solana `BucketMap`
+    // style). This provides durability, not extra capacity: the full entry
+    // set is still resident in `shards`, and the log is replayed back into RAM
+    // on open.
+    persist: Option<Persistence>,
+    // Serialization hooks for the backing files, installed only by
+    // `with_config` where `K` and `V` are `Persist`. Holding them as type-
+    // erased closures keeps the `Persist` bound off the core API, so an
+    // in-memory map works for any `V`.
+    codec: Option<Codec<K, V>>,
+    // Live counters shared with every shard for tuning load/probe/resize
+    // behavior (Solana `BucketMapStats` style).
+    pub stats: Arc<HashMapStats>,
 }
 
-// Tunables
-const MIN_BUCKET_COUNT: usize = 10;
-const MAX_LOAD_FACTOR: f32 = 1.5;
-const MIN_LOAD_FACTOR: f32 = 0.25;
-const RESIZE_FACTOR: f32 = 1.4;
+// A single occupied slot in an open-addressed table.
+struct Node<K, V> {
+    hash: u64,
+    distance_to_initial_bucket: usize,
+    key: K,
+    value: V,
+    // How many times the key has been inserted; the entry is dropped only
+    // once this reaches zero (multiset semantics, as in Solana's bucket map).
+    ref_count: u32,
+}
+
+// Tunables for a single open-addressed table. The table length is always a
+// power of two so the initial bucket is `hash & (len - 1)` rather than a
+// modulo.
+const MIN_BUCKET_COUNT: usize = 16;
+const MAX_LOAD_FACTOR: f32 = 0.75;
+const MIN_LOAD_FACTOR: f32 = 0.2;
+
+// Tunables for the AnchorHash placement layer, expressed as entries per
+// working bucket. A bucket is added once the average shard exceeds the max
+// and removed once it drops below the min.
+const ANCHOR_MAX_LOAD: f32 = 8.0;
+const ANCHOR_MIN_LOAD: f32 = 2.0;
+
+// Initial length of a freshly created backing file, grown on demand.
+const PERSIST_INITIAL_LEN: usize = 1 << 16;
+
+// Backing-file layout: an 8-byte header holding the write cursor (the offset
+// just past the last record), followed by variable-length log records.
+const PERSIST_HEADER_LEN: usize = 8;
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+
+// Rewrite a shard's log once it grows past this multiple of its live byte
+// size. Repeatedly mutating a fixed key set only appends superseding records,
+// so without compaction the backing file would grow without bound.
+const PERSIST_COMPACT_FACTOR: usize = 4;
+
+// Type-erased serialization hooks installed by `with_config`. Keeping them as
+// closures lets the core operations persist without carrying a `Persist` bound.
+type EncodeFn<K, V> = Box<dyn Fn(&K, &V) -> (Vec<u8>, Vec<u8>)>;
+type DecodeFn<K, V> = Box<dyn Fn(&[u8], &[u8]) -> (K, V)>;
+
+struct Codec<K, V> {
+    encode: EncodeFn<K, V>,
+    decode: DecodeFn<K, V>,
+}
 
-impl HashMap {
+impl<K: Hash + Eq, V> HashMap<K, V, DefaultHasher> {
     pub fn new() -> Self {
-        let mut buckets = Vec::with_capacity(MIN_BUCKET_COUNT);
-        for _ in 0..MIN_BUCKET_COUNT {
-            buckets.push(Vec::new());
+        HashMap::with_hasher(DefaultHasher)
+    }
+
+    // Build a map that spreads keys across up to `capacity` buckets using an
+    // AnchorHash placement strategy, so growing or shrinking the bucket count
+    // only remaps a minimal fraction of keys instead of rehashing everything.
+    pub fn with_anchor(capacity: usize) -> Self {
+        HashMap::with_hasher_and_anchor(DefaultHasher, capacity)
+    }
+}
+
+impl<K: Hash + Eq, V, S> HashMap<K, V, S> {
+    // Build a map that hashes keys through a caller-supplied BuildHasher,
+    // e.g. an Fx-style hasher when the default FNV-1a is too slow.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        let stats = Arc::new(HashMapStats::default());
+        HashMap {
+            shards: vec![Table::new(Arc::clone(&stats))],
+            anchor: None,
+            entries_count: 0,
+            total_references: 0,
+            hash_builder,
+            persist: None,
+            codec: None,
+            stats,
+        }
+    }
+
+    // As `with_hasher`, but routes keys through an AnchorHash of `capacity`
+    // buckets (see `with_anchor`).
+    pub fn with_hasher_and_anchor(hash_builder: S, capacity: usize) -> Self {
+        let stats = Arc::new(HashMapStats::default());
+        let mut shards = Vec::with_capacity(capacity);
+        for _ in 0..capacity {
+            shards.push(Table::new(Arc::clone(&stats)));
         }
         HashMap {
-            buckets,
+            shards,
+            anchor: Some(Anchor::new(capacity)),
             entries_count: 0,
+            total_references: 0,
+            hash_builder,
+            persist: None,
+            codec: None,
+            stats,
         }
     }
 
+    pub fn get_entries_count(&self) -> u32 {
+        self.entries_count
+    }
+
+    // Total number of references across all keys (distinct keys counted with
+    // their multiplicity), as opposed to `get_entries_count`'s distinct count.
+    pub fn get_total_references(&self) -> u64 {
+        self.total_references
+    }
+
+    // The configured per-bucket probe bound, when the map is disk-backed.
+    pub fn max_search(&self) -> Option<usize> {
+        self.persist.as_ref().map(|p| p.max_search)
+    }
+
+    pub fn get_buckets_count(&self) -> usize {
+        self.shards.iter().map(|t| t.nodes.len()).sum()
+    }
+
+    pub fn get_bucket(&self, idx: usize) -> Result<Option<(&K, &V)>, String> {
+        let mut remaining = idx;
+        for shard in &self.shards {
+            if remaining < shard.nodes.len() {
+                return Ok(shard.nodes[remaining].as_ref().map(|n| (&n.key, &n.value)));
+            }
+            remaining -= shard.nodes.len();
+        }
+        Err(String::from("bucket index out of bounds"))
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
     // Get item by key
-    pub fn get(&self, key: &str) -> Option<i32> {
-        let bucket = &self.buckets[HashMap::hash(key, self.buckets.len()) as usize];
-        for i in 0..bucket.len() {
-            if bucket[i].0 == key {
-                return Some(bucket[i].1);
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash(key);
+        let result = self.shards[self.shard_index(hash)].get(key, hash);
+        self.stats.record_get(result.is_some());
+        result
+    }
+
+    pub fn set(&mut self, key: K, value: V) {
+        let hash = self.hash(&key);
+        let shard = self.shard_index(hash);
+        self.persist_put(shard, hash, 1, &key, &value);
+        match self.shards[shard].set(key, value, hash) {
+            None => {
+                self.entries_count += 1;
+                self.total_references += 1;
+                self.stats.record_set(false);
+            }
+            Some(previous) => {
+                self.total_references = self.total_references - previous as u64 + 1;
+                self.stats.record_set(true);
             }
         }
-        None
+        self.maybe_compact(shard);
+        self.rebalance();
+        self.record_load();
     }
 
-    pub fn set(&mut self, key: &str, value: i32) {
-        self.delete(key);
+    pub fn delete<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash(key);
+        let shard = self.shard_index(hash);
+        let removed_refs = self.shards[shard].ref_count(key, hash);
+        self.persist_tombstone(shard, hash, key);
+        let removed = self.shards[shard].delete(key, hash);
+        if removed {
+            self.entries_count -= 1;
+            self.total_references -= removed_refs.unwrap_or(0) as u64;
+        }
+        self.stats.record_delete(removed);
+        self.maybe_compact(shard);
+        self.rebalance();
+        self.record_load();
+    }
 
-        let bucket_index = HashMap::hash(key, self.buckets.len()) as usize;
-        let v = &mut self.buckets[bucket_index];
-        v.push((key.to_string(), value));
-        self.entries_count += 1;
+    // Refresh the current/peak load-factor gauges from the live table sizes.
+    fn record_load(&self) {
+        let buckets = self.get_buckets_count();
+        if buckets > 0 {
+            self.stats
+                .record_load((self.entries_count as u64 * 1000) / buckets as u64);
+        }
+    }
 
-        self.resize_if_necessary();
+    // Take a consistent, plainly-readable snapshot of the live statistics.
+    pub fn stats_snapshot(&self) -> HashMapStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    // Increment the reference count of an existing key, returning the new
+    // count (or None if the key is absent).
+    pub fn addref<Q>(&mut self, key: &Q) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash(key);
+        let shard = self.shard_index(hash);
+        let count = self.shards[shard].incref(key, hash);
+        if count.is_some() {
+            self.total_references += 1;
+            self.persist_current(shard, hash, key);
+            self.maybe_compact(shard);
+        }
+        count
     }
 
-    pub fn delete(&mut self, key: &str) {
-        let bucket_index = HashMap::hash(key, self.buckets.len()) as usize;
-        let v = &mut self.buckets[bucket_index];
-        for i in 0..v.len() {
-            if v[i].0 == key {
-                v.remove(i);
+    // Decrement the reference count of a key, deleting it once the count
+    // reaches zero. Returns the remaining count (or None if the key is absent).
+    pub fn unref<Q>(&mut self, key: &Q) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash(key);
+        let shard = self.shard_index(hash);
+        match self.shards[shard].decref(key, hash) {
+            None => None,
+            Some(0) => {
+                self.persist_tombstone(shard, hash, key);
+                self.shards[shard].delete(key, hash);
                 self.entries_count -= 1;
-                return;
+                self.total_references -= 1;
+                self.maybe_compact(shard);
+                self.rebalance();
+                Some(0)
+            }
+            Some(remaining) => {
+                self.total_references -= 1;
+                self.persist_current(shard, hash, key);
+                self.maybe_compact(shard);
+                Some(remaining)
             }
         }
+    }
+
+    // Current reference count of a key, or None if it is absent.
+    pub fn ref_count<Q>(&self, key: &Q) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash(key);
+        self.shards[self.shard_index(hash)].ref_count(key, hash)
+    }
+
+    // Append a put record for `key`/`value` with the given reference count to
+    // the shard's log, if the map is disk-backed. A no-op otherwise.
+    fn persist_put(&mut self, shard: usize, hash: u64, ref_count: u32, key: &K, value: &V) {
+        let (codec, persist) = match (self.codec.as_ref(), self.persist.as_mut()) {
+            (Some(codec), Some(persist)) => (codec, persist),
+            _ => return,
+        };
+        let (key_bytes, value_bytes) = (codec.encode)(key, value);
+        persist.append(shard, OP_PUT, hash, ref_count, &key_bytes, &value_bytes);
+    }
+
+    // Append a put record reflecting the current stored state of `key` (value
+    // and reference count), used after an in-place reference-count change.
+    fn persist_current<Q>(&mut self, shard: usize, hash: u64, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.persist_node(shard, hash, key, OP_PUT);
+    }
+
+    // Append a tombstone record for `key`, used just before it is removed.
+    fn persist_tombstone<Q>(&mut self, shard: usize, hash: u64, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.persist_node(shard, hash, key, OP_DELETE);
+    }
+
+    // Append a record describing the node currently stored under `key`. Does
+    // nothing if the map is not disk-backed or the key is absent.
+    fn persist_node<Q>(&mut self, shard: usize, hash: u64, key: &Q, op: u8)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (codec, persist) = match (self.codec.as_ref(), self.persist.as_mut()) {
+            (Some(codec), Some(persist)) => (codec, persist),
+            _ => return,
+        };
+        if let Some(idx) = self.shards[shard].find(key, hash) {
+            let node = self.shards[shard].nodes[idx].as_ref().unwrap();
+            let (key_bytes, value_bytes) = (codec.encode)(&node.key, &node.value);
+            persist.append(shard, op, node.hash, node.ref_count, &key_bytes, &value_bytes);
+        }
+    }
+
+    // Rewrite the shard's log from its live contents once it has accumulated
+    // enough superseded records to be worth it. A no-op unless the map is
+    // disk-backed and the log has grown past the compaction threshold.
+    fn maybe_compact(&mut self, shard: usize) {
+        if !self
+            .persist
+            .as_ref()
+            .is_some_and(|persist| persist.should_consider_compaction(shard))
+        {
+            return;
+        }
+        let (codec, persist) = match (self.codec.as_ref(), self.persist.as_mut()) {
+            (Some(codec), Some(persist)) => (codec, persist),
+            _ => return,
+        };
+        let mut live = PERSIST_HEADER_LEN;
+        for node in self.shards[shard].nodes.iter().flatten() {
+            let (key_bytes, value_bytes) = (codec.encode)(&node.key, &node.value);
+            live += 21 + key_bytes.len() + value_bytes.len();
+        }
+        let compact = persist.cursor(shard) > live * PERSIST_COMPACT_FACTOR;
+        if compact {
+            persist.reset(shard);
+            for node in self.shards[shard].nodes.iter().flatten() {
+                let (key_bytes, value_bytes) = (codec.encode)(&node.key, &node.value);
+                persist.append(shard, OP_PUT, node.hash, node.ref_count, &key_bytes, &value_bytes);
+            }
+        }
+        persist.schedule_compaction(shard, live, compact);
+    }
+
+    // Hash a key through the configured BuildHasher.
+    fn hash<Q>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.hash_builder.hash_one(key)
+    }
+
+    // Select the shard a key lives in: bucket 0 in single-table mode, or the
+    // AnchorHash bucket otherwise.
+    fn shard_index(&self, hash: u64) -> usize {
+        match &self.anchor {
+            Some(anchor) => anchor.get_bucket(hash),
+            None => 0,
+        }
+    }
+
+    // Add or remove a single AnchorHash bucket when the average shard load
+    // strays outside the tuned band. No-op in single-table mode, where each
+    // table resizes itself.
+    fn rebalance(&mut self) {
+        let (n, capacity) = match &self.anchor {
+            Some(anchor) => (anchor.working_count(), anchor.capacity()),
+            None => return,
+        };
+        let load = self.entries_count as f32 / n as f32;
+        if load > ANCHOR_MAX_LOAD && n < capacity {
+            self.add_shard();
+        } else if load < ANCHOR_MIN_LOAD && n > 1 {
+            self.remove_shard();
+        }
+    }
+
+    // Reclaim a removed bucket and pull in only the keys that now resolve to
+    // it; every other key stays in place (the AnchorHash guarantee).
+    fn add_shard(&mut self) {
+        self.stats.record_resize(true);
+        let b = self.anchor.as_mut().unwrap().add_bucket();
+        for s in 0..self.shards.len() {
+            if s == b {
+                continue;
+            }
+            // Only the keys that now resolve to `b` migrate; every other key
+            // stays exactly where it sits, so shards that contribute nothing
+            // are left untouched rather than drained and rehashed.
+            let anchor = self.anchor.as_ref().unwrap();
+            let moved = self.shards[s].extract_if(|hash| anchor.get_bucket(hash) == b);
+            for node in moved {
+                self.shards[b].insert_raw(node);
+            }
+        }
+    }
+
+    // Retire the most recently added working bucket and redistribute only its
+    // keys across the remaining buckets.
+    fn remove_shard(&mut self) {
+        self.stats.record_resize(false);
+        let b = self.anchor.as_ref().unwrap().working_tail();
+        let drained = self.shards[b].drain();
+        self.anchor.as_mut().unwrap().remove_bucket(b);
+        for node in drained {
+            let dst = self.anchor.as_ref().unwrap().get_bucket(node.hash);
+            self.shards[dst].insert_raw(node);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Persist, V: Persist> HashMap<K, V, DefaultHasher> {
+    // Build a map whose mutations are logged to memory-mapped files on one of
+    // the configured drive directories. If the files already hold entries they
+    // are replayed back in, so the map survives process restarts.
+    //
+    // The disk backing buys durability, not capacity: every entry still lives
+    // in memory, and opening an existing map loads the whole live set back into
+    // RAM. It is a crash-recovery log, not an out-of-core store.
+    pub fn with_config(config: HashMapConfig) -> Self {
+        let persistence = Persistence::open(&config);
+        let stats = Arc::new(HashMapStats::default());
+        let mut table = Table::new(Arc::clone(&stats));
+        // Give the single table the configured probe bound so it grows once a
+        // probe run exceeds `max_search`.
+        table.max_search = Some(config.max_search);
+        let mut map = HashMap {
+            shards: vec![table],
+            anchor: None,
+            entries_count: 0,
+            total_references: 0,
+            hash_builder: DefaultHasher,
+            persist: None,
+            codec: Some(Codec {
+                encode: Box::new(|key: &K, value: &V| (key.to_bytes(), value.to_bytes())),
+                decode: Box::new(|key: &[u8], value: &[u8]| {
+                    (K::from_bytes(key), V::from_bytes(value))
+                }),
+            }),
+            stats,
+        };
+        map.replay(&persistence);
+        map.persist = Some(persistence);
+        map
+    }
+
+    // Replay the append-only log back into memory, one record at a time, then
+    // recompute the aggregate counts from the reconstructed tables.
+    fn replay(&mut self, persistence: &Persistence) {
+        let cursor = persistence.cursor(0);
+        let mut offset = PERSIST_HEADER_LEN;
+        while offset < cursor {
+            let (next, record) = persistence.read_record(0, offset);
+            offset = next;
+            let (key, value) =
+                (self.codec.as_ref().unwrap().decode)(&record.key, &record.value);
+            let shard = self.shard_index(record.hash);
+            match record.op {
+                OP_DELETE => {
+                    self.shards[shard].delete(&key, record.hash);
+                }
+                _ => {
+                    self.shards[shard].upsert(key, value, record.ref_count, record.hash);
+                }
+            }
+        }
+        for shard in &self.shards {
+            for node in shard.nodes.iter().flatten() {
+                self.entries_count += 1;
+                self.total_references += node.ref_count as u64;
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Default for HashMap<K, V, DefaultHasher> {
+    fn default() -> Self {
+        HashMap::new()
+    }
+}
+
+// A single Robin Hood open-addressed table, the storage behind one bucket.
+struct Table<K, V> {
+    nodes: Vec<Option<Node<K, V>>>,
+    entries_count: u32,
+    max_distance_to_initial_bucket: usize,
+    // When set (disk-backed maps), the table grows whenever its longest probe
+    // run exceeds this bound.
+    max_search: Option<usize>,
+    stats: Arc<HashMapStats>,
+}
+
+impl<K: Hash + Eq, V> Table<K, V> {
+    fn new(stats: Arc<HashMapStats>) -> Self {
+        let mut nodes = Vec::with_capacity(MIN_BUCKET_COUNT);
+        for _ in 0..MIN_BUCKET_COUNT {
+            nodes.push(None);
+        }
+        Table {
+            nodes,
+            entries_count: 0,
+            max_distance_to_initial_bucket: 0,
+            max_search: None,
+            stats,
+        }
+    }
+
+    fn get<Q>(&self, key: &Q, hash: u64) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key, hash)
+            .map(|idx| &self.nodes[idx].as_ref().unwrap().value)
+    }
+
+    // Insert or overwrite a key, resetting its reference count to 1. Returns
+    // the previous reference count if the key was already present, or None if
+    // it was newly inserted.
+    fn set(&mut self, key: K, value: V, hash: u64) -> Option<u32> {
+        if let Some(idx) = self.find(&key, hash) {
+            let node = self.nodes[idx].as_mut().unwrap();
+            let previous = node.ref_count;
+            node.value = value;
+            node.ref_count = 1;
+            return Some(previous);
+        }
+        self.insert_node(Node {
+            hash,
+            distance_to_initial_bucket: 0,
+            key,
+            value,
+            ref_count: 1,
+        });
+        self.entries_count += 1;
         self.resize_if_necessary();
+        None
     }
 
-    pub fn get_bucket(&self, idx: usize) -> Result<&Vec<(String, i32)>, String> {
-        match self.buckets.get(idx) {
-            Some(bucket) => Ok(bucket),
-            None => Err(String::from("bucket index out of bounds")),
+    // Insert or overwrite a key with an explicit reference count, used when
+    // replaying the persistence log. Unlike `set`, the count is preserved
+    // rather than reset to 1.
+    fn upsert(&mut self, key: K, value: V, ref_count: u32, hash: u64) {
+        if let Some(idx) = self.find(&key, hash) {
+            let node = self.nodes[idx].as_mut().unwrap();
+            node.value = value;
+            node.ref_count = ref_count;
+            return;
         }
+        self.insert_node(Node {
+            hash,
+            distance_to_initial_bucket: 0,
+            key,
+            value,
+            ref_count,
+        });
+        self.entries_count += 1;
+        self.resize_if_necessary();
     }
 
-    pub fn get_entries_count(&self) -> u32 {
-        return self.entries_count;
+    // Bump a key's reference count, returning the new count.
+    fn incref<Q>(&mut self, key: &Q, hash: u64) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = self.find(key, hash)?;
+        let node = self.nodes[idx].as_mut().unwrap();
+        node.ref_count += 1;
+        Some(node.ref_count)
     }
 
-    pub fn get_buckets_count(&self) -> usize {
-        return self.buckets.len();
+    // Drop a key's reference count by one, returning the new count.
+    fn decref<Q>(&mut self, key: &Q, hash: u64) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = self.find(key, hash)?;
+        let node = self.nodes[idx].as_mut().unwrap();
+        node.ref_count -= 1;
+        Some(node.ref_count)
+    }
+
+    fn ref_count<Q>(&self, key: &Q, hash: u64) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key, hash)
+            .map(|idx| self.nodes[idx].as_ref().unwrap().ref_count)
+    }
+
+    fn delete<Q>(&mut self, key: &Q, hash: u64) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = match self.find(key, hash) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        self.remove_at(idx);
+        self.resize_if_necessary();
+        true
+    }
+
+    // Backward-shift deletion of the node at `idx`: clear the slot, then pull
+    // each following node back one position until we hit an empty slot or a
+    // node already sitting in its initial bucket (distance 0). Returns the
+    // node that was removed.
+    fn remove_at(&mut self, idx: usize) -> Node<K, V> {
+        let mask = self.nodes.len() - 1;
+        let removed = self.nodes[idx].take().unwrap();
+        self.entries_count -= 1;
+
+        let mut prev = idx;
+        let mut cur = (idx + 1) & mask;
+        loop {
+            match self.nodes[cur].as_ref() {
+                Some(node) if node.distance_to_initial_bucket > 0 => {
+                    let mut node = self.nodes[cur].take().unwrap();
+                    node.distance_to_initial_bucket -= 1;
+                    self.nodes[prev] = Some(node);
+                    prev = cur;
+                    cur = (cur + 1) & mask;
+                }
+                _ => break,
+            }
+        }
+        removed
+    }
+
+    // Remove and return every node whose hash satisfies `matches`, leaving the
+    // remaining nodes in place. Used on a bucket add so that only the keys that
+    // now resolve elsewhere are touched, never the whole table.
+    fn extract_if<F>(&mut self, matches: F) -> Vec<Node<K, V>>
+    where
+        F: Fn(u64) -> bool,
+    {
+        let mut extracted = Vec::new();
+        // Each removal backward-shifts later nodes, so re-scan from the start
+        // until none match rather than tracking slots that move underneath us.
+        while let Some(idx) = self
+            .nodes
+            .iter()
+            .position(|slot| slot.as_ref().is_some_and(|node| matches(node.hash)))
+        {
+            extracted.push(self.remove_at(idx));
+        }
+        self.resize_if_necessary();
+        extracted
+    }
+
+    // Locate the slot holding `key`, or None if absent. The Robin Hood
+    // invariant lets us stop early: once we reach a node closer to its own
+    // initial bucket than our current probe distance, the key cannot exist.
+    fn find<Q>(&self, key: &Q, hash: u64) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mask = self.nodes.len() - 1;
+        let mut idx = (hash as usize) & mask;
+        let mut distance = 0;
+        loop {
+            self.stats.record_lookup_probe();
+            match self.nodes[idx].as_ref() {
+                None => return None,
+                Some(node) => {
+                    if distance > node.distance_to_initial_bucket {
+                        return None;
+                    }
+                    if node.hash == hash && node.key.borrow() == key {
+                        return Some(idx);
+                    }
+                }
+            }
+            if distance > self.max_distance_to_initial_bucket {
+                return None;
+            }
+            distance += 1;
+            idx = (idx + 1) & mask;
+        }
     }
 
-    // uses the FNV-1a hash
-    fn hash(key: &str, bucket_count: usize) -> usize {
-        const FNV_OFFSET_BASIS: u64 = 14695981039346656037;
-        const FNV_PRIME: u64 = 1099511628211;
+    // Reinsert a pre-built node (e.g. during resize or shard migration),
+    // resetting its probe distance first.
+    fn insert_raw(&mut self, mut node: Node<K, V>) {
+        node.distance_to_initial_bucket = 0;
+        self.insert_node(node);
+        self.entries_count += 1;
+        self.resize_if_necessary();
+    }
 
-        let mut hash = FNV_OFFSET_BASIS;
-        for octet in key.as_bytes() {
-            hash ^= *octet as u64;
-            hash = hash.wrapping_mul(FNV_PRIME);
+    // Place `node` using Robin Hood probing: whenever we meet an occupant that
+    // is closer to its initial bucket than we are, we steal its slot and carry
+    // on reinserting the node we displaced.
+    fn insert_node(&mut self, mut node: Node<K, V>) {
+        let mask = self.nodes.len() - 1;
+        let mut idx = (node.hash as usize) & mask;
+        loop {
+            self.stats.record_insert_probe();
+            match self.nodes[idx].as_mut() {
+                None => {
+                    if node.distance_to_initial_bucket > self.max_distance_to_initial_bucket {
+                        self.max_distance_to_initial_bucket = node.distance_to_initial_bucket;
+                    }
+                    self.stats
+                        .record_max_probe_distance(self.max_distance_to_initial_bucket);
+                    self.nodes[idx] = Some(node);
+                    return;
+                }
+                Some(occupant) => {
+                    if occupant.distance_to_initial_bucket < node.distance_to_initial_bucket {
+                        std::mem::swap(occupant, &mut node);
+                    }
+                }
+            }
+            node.distance_to_initial_bucket += 1;
+            if node.distance_to_initial_bucket > self.max_distance_to_initial_bucket {
+                self.max_distance_to_initial_bucket = node.distance_to_initial_bucket;
+            }
+            idx = (idx + 1) & mask;
         }
+    }
 
-        (hash as usize) % bucket_count
+    // Remove every node, leaving the table empty at its minimum size.
+    fn drain(&mut self) -> Vec<Node<K, V>> {
+        let mut nodes = Vec::with_capacity(MIN_BUCKET_COUNT);
+        for _ in 0..MIN_BUCKET_COUNT {
+            nodes.push(None);
+        }
+        self.entries_count = 0;
+        self.max_distance_to_initial_bucket = 0;
+        std::mem::replace(&mut self.nodes, nodes)
+            .into_iter()
+            .flatten()
+            .collect()
     }
 
     fn resize_if_necessary(&mut self) {
-        let load_factor = self.entries_count as f32 / self.buckets.len() as f32;
+        let load_factor = self.entries_count as f32 / self.nodes.len() as f32;
+
+        let over_search = self
+            .max_search
+            .is_some_and(|bound| self.max_distance_to_initial_bucket > bound);
 
-        if load_factor > MAX_LOAD_FACTOR {
-            let new_size = (self.buckets.len() as f32 * RESIZE_FACTOR).ceil() as usize;
-            self.resize(new_size);
-        } else if load_factor < MIN_LOAD_FACTOR && self.buckets.len() / 2 > MIN_BUCKET_COUNT {
-            let new_size = (self.buckets.len() as f32 / RESIZE_FACTOR).ceil() as usize;
-            self.resize(new_size);
+        if load_factor > MAX_LOAD_FACTOR || over_search {
+            self.resize(self.nodes.len() * 2);
+        } else if load_factor < MIN_LOAD_FACTOR && self.nodes.len() / 2 >= MIN_BUCKET_COUNT {
+            self.resize(self.nodes.len() / 2);
         }
     }
 
     fn resize(&mut self, new_bucket_count: usize) {
-        println!("{}", new_bucket_count);
-        let mut new_buckets = Vec::with_capacity(new_bucket_count);
-        for _ in 0..new_bucket_count {
-            new_buckets.push(Vec::new());
+        self.stats.record_resize(new_bucket_count > self.nodes.len());
+        let old = std::mem::replace(&mut self.nodes, {
+            let mut nodes = Vec::with_capacity(new_bucket_count);
+            for _ in 0..new_bucket_count {
+                nodes.push(None);
+            }
+            nodes
+        });
+        self.max_distance_to_initial_bucket = 0;
+
+        for mut node in old.into_iter().flatten() {
+            node.distance_to_initial_bucket = 0;
+            self.insert_node(node);
+        }
+    }
+}
+
+// AnchorHash (Algorithm 3): a consistent-hash bucket assignment that keeps
+// remapping minimal when buckets are added or removed. `capacity` is the
+// fixed maximum number of buckets; `working_count` of them are live at any
+// time. See Mendelson et al., "AnchorHash".
+struct Anchor {
+    a: Vec<usize>, // A: 0 == working, else working-set size when removed
+    k: Vec<usize>, // K: successor bucket of a removed bucket
+    l: Vec<usize>, // L: location of a bucket within W
+    w: Vec<usize>, // W: working-bucket list
+    r: Vec<usize>, // R: LIFO stack of removed buckets
+    n: usize,      // number of working buckets
+}
+
+impl Anchor {
+    fn new(capacity: usize) -> Self {
+        assert!(capacity >= 1, "anchor capacity must be at least 1");
+        let mut anchor = Anchor {
+            a: vec![0; capacity],
+            k: (0..capacity).collect(),
+            l: (0..capacity).collect(),
+            w: (0..capacity).collect(),
+            r: Vec::new(),
+            n: capacity,
+        };
+        // Start with a single working bucket; the rest begin removed and are
+        // reclaimed as the map grows.
+        for b in (1..capacity).rev() {
+            anchor.remove_bucket(b);
+        }
+        anchor
+    }
+
+    fn capacity(&self) -> usize {
+        self.a.len()
+    }
+
+    fn working_count(&self) -> usize {
+        self.n
+    }
+
+    fn working_tail(&self) -> usize {
+        self.w[self.n - 1]
+    }
+
+    // Resolve a key's hash to a working bucket.
+    fn get_bucket(&self, hash: u64) -> usize {
+        let mut b = (hash % self.capacity() as u64) as usize;
+        while self.a[b] > 0 {
+            let mut h = (seeded(hash, b) % self.a[b] as u64) as usize;
+            while self.a[h] >= self.a[b] {
+                h = self.k[h];
+            }
+            b = h;
+        }
+        b
+    }
+
+    fn add_bucket(&mut self) -> usize {
+        let b = self.r.pop().expect("no removed buckets available to add");
+        self.a[b] = 0;
+        self.w[self.n] = b;
+        self.l[b] = self.n;
+        self.k[b] = b;
+        self.n += 1;
+        b
+    }
+
+    fn remove_bucket(&mut self, b: usize) {
+        self.n -= 1;
+        self.a[b] = self.n;
+        self.w[self.l[b]] = self.w[self.n];
+        self.l[self.w[self.n]] = self.l[b];
+        self.k[b] = self.w[self.n];
+        self.r.push(b);
+    }
+}
+
+// Remix a key hash with a bucket seed (splitmix64 finalizer) so that the
+// per-bucket probe in `get_bucket` is independent of the top-level hash.
+fn seeded(hash: u64, seed: usize) -> u64 {
+    let mut x = hash ^ (seed as u64).wrapping_mul(0x9E3779B97F4A7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^ (x >> 31)
+}
+
+// Live, atomically-updated counters describing load, probing, and resize
+// behavior. Shared between the map and its tables so lookups (which only take
+// `&self`) can still record into it.
+#[derive(Default)]
+pub struct HashMapStats {
+    resizes_grow: AtomicU64,
+    resizes_shrink: AtomicU64,
+    get_hits: AtomicU64,
+    get_misses: AtomicU64,
+    set_hits: AtomicU64,
+    set_misses: AtomicU64,
+    delete_hits: AtomicU64,
+    delete_misses: AtomicU64,
+    total_lookup_probe_length: AtomicU64,
+    total_insert_probe_length: AtomicU64,
+    max_probe_distance: AtomicU64,
+    current_load_permille: AtomicU64,
+    peak_load_permille: AtomicU64,
+}
+
+impl HashMapStats {
+    fn record_resize(&self, grow: bool) {
+        let counter = if grow {
+            &self.resizes_grow
+        } else {
+            &self.resizes_shrink
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_get(&self, hit: bool) {
+        let counter = if hit { &self.get_hits } else { &self.get_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_set(&self, hit: bool) {
+        let counter = if hit { &self.set_hits } else { &self.set_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_delete(&self, hit: bool) {
+        let counter = if hit {
+            &self.delete_hits
+        } else {
+            &self.delete_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_lookup_probe(&self) {
+        self.total_lookup_probe_length
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_insert_probe(&self) {
+        self.total_insert_probe_length
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_max_probe_distance(&self, distance: usize) {
+        self.max_probe_distance
+            .fetch_max(distance as u64, Ordering::Relaxed);
+    }
+
+    fn record_load(&self, permille: u64) {
+        self.current_load_permille.store(permille, Ordering::Relaxed);
+        self.peak_load_permille
+            .fetch_max(permille, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HashMapStatsSnapshot {
+        HashMapStatsSnapshot {
+            resizes_grow: self.resizes_grow.load(Ordering::Relaxed),
+            resizes_shrink: self.resizes_shrink.load(Ordering::Relaxed),
+            get_hits: self.get_hits.load(Ordering::Relaxed),
+            get_misses: self.get_misses.load(Ordering::Relaxed),
+            set_hits: self.set_hits.load(Ordering::Relaxed),
+            set_misses: self.set_misses.load(Ordering::Relaxed),
+            delete_hits: self.delete_hits.load(Ordering::Relaxed),
+            delete_misses: self.delete_misses.load(Ordering::Relaxed),
+            total_lookup_probe_length: self.total_lookup_probe_length.load(Ordering::Relaxed),
+            total_insert_probe_length: self.total_insert_probe_length.load(Ordering::Relaxed),
+            max_probe_distance: self.max_probe_distance.load(Ordering::Relaxed),
+            current_load_factor: self.current_load_permille.load(Ordering::Relaxed) as f32 / 1000.0,
+            peak_load_factor: self.peak_load_permille.load(Ordering::Relaxed) as f32 / 1000.0,
+        }
+    }
+}
+
+// A plain, copyable view of `HashMapStats` for logging or assertions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HashMapStatsSnapshot {
+    pub resizes_grow: u64,
+    pub resizes_shrink: u64,
+    pub get_hits: u64,
+    pub get_misses: u64,
+    pub set_hits: u64,
+    pub set_misses: u64,
+    pub delete_hits: u64,
+    pub delete_misses: u64,
+    pub total_lookup_probe_length: u64,
+    pub total_insert_probe_length: u64,
+    pub max_probe_distance: u64,
+    pub current_load_factor: f32,
+    pub peak_load_factor: f32,
+}
+
+// Configuration for a disk-backed map.
+pub struct HashMapConfig {
+    // Directories the backing files may live on; the first is used. When
+    // `None`, a subdirectory of the system temp dir is used.
+    pub drives: Option<Vec<PathBuf>>,
+    // Upper bound on the probe distance within the in-memory table; the table
+    // grows once its longest probe run exceeds it.
+    pub max_search: usize,
+    // Erase the backing files when the map is dropped (handy for tests).
+    pub erase_on_drop: bool,
+}
+
+impl Default for HashMapConfig {
+    fn default() -> Self {
+        HashMapConfig {
+            drives: None,
+            max_search: MIN_BUCKET_COUNT,
+            erase_on_drop: false,
         }
+    }
+}
+
+// Serialization of keys and values into the raw bytes stored in a bucket
+// file. Implemented for the scalar and string types a bucket map stores.
+pub trait Persist {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Self;
+}
 
-        for bucket in &self.buckets {
-            for item in bucket {
-                new_buckets[HashMap::hash(&item.0, new_bucket_count)]
-                    .push((item.0.clone(), item.1));
+impl Persist for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+macro_rules! impl_persist_int {
+    ($($t:ty),*) => {$(
+        impl Persist for $t {
+            fn to_bytes(&self) -> Vec<u8> {
+                self.to_le_bytes().to_vec()
+            }
+
+            fn from_bytes(bytes: &[u8]) -> Self {
+                let mut buf = [0u8; std::mem::size_of::<$t>()];
+                buf.copy_from_slice(bytes);
+                <$t>::from_le_bytes(buf)
             }
         }
-        self.buckets = new_buckets;
+    )*};
+}
+
+impl_persist_int!(i32, u32, i64, u64, usize);
+
+// One memory-mapped file per shard, used as an append-only log. After the
+// 8-byte cursor header each record is
+// `[op][hash][ref_count][key_len][value_len][key bytes][value bytes]`; the
+// latest record for a key wins when the log is replayed.
+struct ShardFile {
+    path: PathBuf,
+    file: File,
+    mmap: MmapMut,
+    // Cursor value at which the next compaction check is worthwhile. Doubled
+    // while compaction is deferred and reset to a multiple of the live size
+    // after a rewrite, so the O(n) live-size scan does not run on every append.
+    compact_at: usize,
+}
+
+// A single decoded log record, as read back during replay.
+struct RawRecord {
+    op: u8,
+    hash: u64,
+    ref_count: u32,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+struct Persistence {
+    dir: PathBuf,
+    files: Vec<ShardFile>,
+    max_search: usize,
+    erase_on_drop: bool,
+}
+
+impl Persistence {
+    fn open(config: &HashMapConfig) -> Self {
+        let dir = match &config.drives {
+            Some(drives) if !drives.is_empty() => drives[0].clone(),
+            _ => std::env::temp_dir().join("rust_hash_map"),
+        };
+        std::fs::create_dir_all(&dir).expect("failed to create drive directory");
+        let mut persistence = Persistence {
+            dir,
+            files: Vec::new(),
+            max_search: config.max_search,
+            erase_on_drop: config.erase_on_drop,
+        };
+        persistence.ensure_file(0);
+        persistence
+    }
+
+    // Make sure a backing file exists for shard `idx`.
+    fn ensure_file(&mut self, idx: usize) {
+        while self.files.len() <= idx {
+            let path = self.dir.join(format!("bucket{}.data", self.files.len()));
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .expect("failed to open backing file");
+            if file.metadata().expect("failed to stat backing file").len() == 0 {
+                file.set_len(PERSIST_INITIAL_LEN as u64)
+                    .expect("failed to size backing file");
+            }
+            let mmap = unsafe { MmapMut::map_mut(&file).expect("failed to map backing file") };
+            self.files.push(ShardFile {
+                path,
+                file,
+                mmap,
+                compact_at: PERSIST_INITIAL_LEN,
+            });
+        }
+    }
+
+    // The offset just past the last record written to shard `idx`; records
+    // begin right after the header.
+    fn cursor(&self, idx: usize) -> usize {
+        let bytes = &self.files[idx].mmap;
+        let cursor = u64::from_le_bytes(bytes[0..8].try_into().unwrap()) as usize;
+        cursor.max(PERSIST_HEADER_LEN)
+    }
+
+    // Read a single record at `offset`, returning the offset of the next
+    // record alongside it.
+    fn read_record(&self, idx: usize, offset: usize) -> (usize, RawRecord) {
+        let bytes = &self.files[idx].mmap;
+        let op = bytes[offset];
+        let hash = u64::from_le_bytes(bytes[offset + 1..offset + 9].try_into().unwrap());
+        let ref_count = u32::from_le_bytes(bytes[offset + 9..offset + 13].try_into().unwrap());
+        let klen = u32::from_le_bytes(bytes[offset + 13..offset + 17].try_into().unwrap()) as usize;
+        let vlen = u32::from_le_bytes(bytes[offset + 17..offset + 21].try_into().unwrap()) as usize;
+        let key_start = offset + 21;
+        let key = bytes[key_start..key_start + klen].to_vec();
+        let value = bytes[key_start + klen..key_start + klen + vlen].to_vec();
+        (
+            key_start + klen + vlen,
+            RawRecord {
+                op,
+                hash,
+                ref_count,
+                key,
+                value,
+            },
+        )
+    }
+
+    // Append one record to the shard's log and advance the cursor, growing the
+    // file (reallocate + remap) if the record no longer fits.
+    fn append(
+        &mut self,
+        idx: usize,
+        op: u8,
+        hash: u64,
+        ref_count: u32,
+        key: &[u8],
+        value: &[u8],
+    ) {
+        self.ensure_file(idx);
+        let record_len = 21 + key.len() + value.len();
+        let cursor = self.cursor(idx);
+
+        let shard = &mut self.files[idx];
+        if cursor + record_len > shard.mmap.len() {
+            let new_len = std::cmp::max(cursor + record_len, shard.mmap.len() * 2);
+            shard
+                .file
+                .set_len(new_len as u64)
+                .expect("failed to grow backing file");
+            shard.mmap =
+                unsafe { MmapMut::map_mut(&shard.file).expect("failed to remap backing file") };
+        }
+
+        let mut at = cursor;
+        shard.mmap[at] = op;
+        at += 1;
+        shard.mmap[at..at + 8].copy_from_slice(&hash.to_le_bytes());
+        at += 8;
+        shard.mmap[at..at + 4].copy_from_slice(&ref_count.to_le_bytes());
+        at += 4;
+        shard.mmap[at..at + 4].copy_from_slice(&(key.len() as u32).to_le_bytes());
+        at += 4;
+        shard.mmap[at..at + 4].copy_from_slice(&(value.len() as u32).to_le_bytes());
+        at += 4;
+        shard.mmap[at..at + key.len()].copy_from_slice(key);
+        at += key.len();
+        shard.mmap[at..at + value.len()].copy_from_slice(value);
+
+        let new_cursor = (cursor + record_len) as u64;
+        shard.mmap[0..8].copy_from_slice(&new_cursor.to_le_bytes());
+        shard.mmap.flush().expect("failed to flush backing file");
+    }
+
+    // Whether the shard's log has grown enough that a compaction check pays
+    // for the O(n) live-size scan it needs.
+    fn should_consider_compaction(&self, idx: usize) -> bool {
+        idx < self.files.len() && self.cursor(idx) >= self.files[idx].compact_at
+    }
+
+    // Reset a shard's log to an empty state (cursor back to the header). The
+    // stale tail past the header is ignored on replay, which is bounded by the
+    // cursor, so the live set can be re-appended over it.
+    fn reset(&mut self, idx: usize) {
+        self.ensure_file(idx);
+        let header = (PERSIST_HEADER_LEN as u64).to_le_bytes();
+        self.files[idx].mmap[0..8].copy_from_slice(&header);
+        self.files[idx].mmap.flush().expect("failed to flush backing file");
+    }
+
+    // Record the outcome of a compaction attempt and schedule the next one.
+    // After a rewrite the log sits at `live_bytes`, so the next check waits
+    // until it has grown by the configured factor again; a deferred attempt
+    // (too little garbage to bother) simply doubles the threshold.
+    fn schedule_compaction(&mut self, idx: usize, live_bytes: usize, compacted: bool) {
+        let next = if compacted {
+            live_bytes.saturating_mul(PERSIST_COMPACT_FACTOR)
+        } else {
+            self.cursor(idx).saturating_mul(2)
+        };
+        self.files[idx].compact_at = next.max(PERSIST_INITIAL_LEN);
+    }
+
+    // Remove the backing files and the drive directory if it is now empty.
+    fn erase(&self) {
+        for shard in &self.files {
+            let _ = std::fs::remove_file(&shard.path);
+        }
+        let _ = std::fs::remove_dir(&self.dir);
+    }
+}
+
+impl<K, V, S> Drop for HashMap<K, V, S> {
+    fn drop(&mut self) {
+        if let Some(persist) = &self.persist {
+            if persist.erase_on_drop {
+                persist.erase();
+            }
+        }
+    }
+}
+
+// The FNV-1a BuildHasher used unless the caller injects their own.
+#[derive(Clone, Copy, Default)]
+pub struct DefaultHasher;
+
+impl BuildHasher for DefaultHasher {
+    type Hasher = Fnv1a;
+
+    fn build_hasher(&self) -> Fnv1a {
+        Fnv1a::new()
+    }
+}
+
+// FNV-1a hasher.
+pub struct Fnv1a {
+    state: u64,
+}
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 14695981039346656037;
+    const PRIME: u64 = 1099511628211;
+
+    fn new() -> Self {
+        Fnv1a {
+            state: Fnv1a::OFFSET_BASIS,
+        }
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a::new()
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.state
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for octet in bytes {
+            self.state ^= *octet as u64;
+            self.state = self.state.wrapping_mul(Fnv1a::PRIME);
+        }
     }
 }
 
@@ -122,14 +1299,14 @@ mod tests {
     fn correct_item_count() {
         let mut hm = HashMap::new();
         for i in 0..100 {
-            hm.set(&(&i.to_string()), i);
+            hm.set(i.to_string(), i);
         }
         assert_eq!(hm.get_entries_count(), 100);
     }
 
     #[test]
     fn item_not_found() {
-        let hm = HashMap::new();
+        let hm: HashMap<String, i32> = HashMap::new();
         let key = "test";
         assert_eq!(None, hm.get(key));
     }
@@ -137,8 +1314,143 @@ mod tests {
     #[test]
     fn item_found() {
         let mut hm = HashMap::new();
-        hm.set("test", 5);
+        hm.set(String::from("test"), 5);
         let key = "test";
-        assert_eq!(Some(5), hm.get(key));
+        assert_eq!(Some(&5), hm.get(key));
+    }
+
+    #[test]
+    fn survives_collisions_and_deletes() {
+        let mut hm = HashMap::new();
+        for i in 0..200 {
+            hm.set(i.to_string(), i);
+        }
+        for i in (0..200).step_by(2) {
+            hm.delete(&i.to_string());
+        }
+        for i in 0..200 {
+            if i % 2 == 0 {
+                assert_eq!(None, hm.get(&i.to_string()));
+            } else {
+                assert_eq!(Some(&i), hm.get(&i.to_string()));
+            }
+        }
+    }
+
+    #[test]
+    fn anchor_placement_round_trips() {
+        let mut hm = HashMap::with_anchor(64);
+        for i in 0..500 {
+            hm.set(i.to_string(), i);
+        }
+        assert_eq!(hm.get_entries_count(), 500);
+        for i in 0..500 {
+            assert_eq!(Some(&i), hm.get(&i.to_string()));
+        }
+        for i in 0..500 {
+            hm.delete(&i.to_string());
+        }
+        assert_eq!(hm.get_entries_count(), 0);
+    }
+
+    #[test]
+    fn persists_and_reloads() {
+        let dir = std::env::temp_dir().join("rust_hash_map_test_persist");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        {
+            let mut hm: HashMap<String, i32> = HashMap::with_config(HashMapConfig {
+                drives: Some(vec![dir.clone()]),
+                max_search: 16,
+                erase_on_drop: false,
+            });
+            hm.set("a".to_string(), 1);
+            hm.set("b".to_string(), 2);
+        }
+
+        let hm: HashMap<String, i32> = HashMap::with_config(HashMapConfig {
+            drives: Some(vec![dir.clone()]),
+            max_search: 16,
+            erase_on_drop: true,
+        });
+        assert_eq!(hm.get("a"), Some(&1));
+        assert_eq!(hm.get("b"), Some(&2));
+    }
+
+    #[test]
+    fn compaction_bounds_backing_file() {
+        let dir = std::env::temp_dir().join("rust_hash_map_test_compaction");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        {
+            let mut hm: HashMap<String, i32> = HashMap::with_config(HashMapConfig {
+                drives: Some(vec![dir.clone()]),
+                max_search: 16,
+                erase_on_drop: false,
+            });
+            // Mutate a fixed key set far more times than it has distinct keys.
+            // Without compaction every write would append forever; with it the
+            // log is rewritten from the live set and the file stays bounded.
+            for round in 0..3000 {
+                for k in 0..8 {
+                    hm.set(format!("key{}", k), round);
+                }
+            }
+            let size = std::fs::metadata(dir.join("bucket0.data"))
+                .expect("backing file missing")
+                .len();
+            assert!(size < 256 * 1024, "backing file grew to {} bytes", size);
+        }
+
+        // The live values survive the rewrites and a reload.
+        let hm: HashMap<String, i32> = HashMap::with_config(HashMapConfig {
+            drives: Some(vec![dir.clone()]),
+            max_search: 16,
+            erase_on_drop: true,
+        });
+        for k in 0..8 {
+            assert_eq!(hm.get(&format!("key{}", k)), Some(&2999));
+        }
+    }
+
+    #[test]
+    fn reference_counting() {
+        let mut hm = HashMap::new();
+        hm.set(String::from("x"), 10);
+        assert_eq!(hm.ref_count("x"), Some(1));
+
+        assert_eq!(hm.addref("x"), Some(2));
+        assert_eq!(hm.addref("x"), Some(3));
+        assert_eq!(hm.get_entries_count(), 1);
+        assert_eq!(hm.get_total_references(), 3);
+
+        assert_eq!(hm.unref("x"), Some(2));
+        assert_eq!(hm.unref("x"), Some(1));
+        assert_eq!(hm.get("x"), Some(&10));
+
+        assert_eq!(hm.unref("x"), Some(0));
+        assert_eq!(hm.get("x"), None);
+        assert_eq!(hm.get_entries_count(), 0);
+        assert_eq!(hm.get_total_references(), 0);
+    }
+
+    #[test]
+    fn tracks_statistics() {
+        let mut hm = HashMap::new();
+        for i in 0..50 {
+            hm.set(i.to_string(), i);
+        }
+        assert_eq!(hm.get(&"10".to_string()), Some(&10));
+        assert_eq!(hm.get(&"999".to_string()), None);
+        hm.delete(&"10".to_string());
+
+        let snapshot = hm.stats_snapshot();
+        assert_eq!(snapshot.get_hits, 1);
+        assert_eq!(snapshot.get_misses, 1);
+        assert_eq!(snapshot.delete_hits, 1);
+        assert_eq!(snapshot.set_misses, 50);
+        assert!(snapshot.resizes_grow > 0);
+        assert!(snapshot.peak_load_factor > 0.0);
+        assert!(snapshot.total_insert_probe_length > 0);
     }
 }